@@ -0,0 +1,188 @@
+use std::vec::Vec;
+
+use crate::{
+    archetype::ArchetypeId,
+    component::ComponentId,
+    storage::{SubStorageId, SubStorages},
+};
+
+/// An ordered set of components that should be stored contiguously within a
+/// [`SubStorageData`](crate::storage::SubStorageData)'s `archetypes` list.
+///
+/// Declaring a group for `(A, B)` means every archetype containing both `A`
+/// and `B` is kept in one contiguous run, regardless of what other
+/// components each archetype additionally has. A query over `(A, B)` can
+/// then walk that run without skipping over unrelated archetypes, instead of
+/// visiting every archetype in the sub-storage.
+///
+/// Groups declared on the same sub-storage must nest: any two groups must
+/// either be disjoint or have one's component set be a subset of the
+/// other's. [`SubStorages::define_group`] enforces this, because a
+/// partial overlap (two groups that share some but not all components, with
+/// neither contained in the other) has no placement that keeps *both*
+/// groups' matching archetypes contiguous at once.
+#[derive(Clone)]
+pub struct Group {
+    components: Vec<ComponentId>,
+}
+
+impl Group {
+    fn matches(&self, archetype_components: &[ComponentId]) -> bool {
+        self.components
+            .iter()
+            .all(|component| archetype_components.contains(component))
+    }
+
+    /// Whether `self` and `other` can coexist as groups on the same
+    /// sub-storage: disjoint, or one a subset of the other.
+    fn nests_with(&self, other: &Group) -> bool {
+        let self_subset_of_other = self
+            .components
+            .iter()
+            .all(|component| other.components.contains(component));
+        let other_subset_of_self = other
+            .components
+            .iter()
+            .all(|component| self.components.contains(component));
+        let disjoint = !self
+            .components
+            .iter()
+            .any(|component| other.components.contains(component));
+
+        self_subset_of_other || other_subset_of_self || disjoint
+    }
+}
+
+/// Chooses where, within a list of archetypes whose components are given by
+/// `component_sets` (same order as the list), a new archetype with
+/// `components` should be inserted so it satisfies every group in `groups`
+/// that it matches. Returns `None` if `components` doesn't satisfy any
+/// group, in which case the caller should just append it to the end.
+///
+/// Matching groups are narrowed from *least*-specific to *most*-specific:
+/// each step looks only within the contiguous run already established by
+/// the previous (less specific) group, so the final position stays inside
+/// every matching group's run at once instead of just the most specific one
+/// (which, found in isolation, could land outside a broader matching
+/// group's existing contiguous block and split it).
+fn grouped_insertion_point(
+    groups: &[Group],
+    component_sets: &[Vec<ComponentId>],
+    components: &[ComponentId],
+) -> Option<usize> {
+    let mut matching: Vec<&Group> = groups.iter().filter(|group| group.matches(components)).collect();
+    if matching.is_empty() {
+        return None;
+    }
+    matching.sort_by_key(|group| group.components.len());
+
+    let mut bracket = 0..component_sets.len();
+    for group in matching {
+        let within = &component_sets[bracket.clone()];
+        let Some(first) = within.iter().position(|set| group.matches(set)) else {
+            // No existing member of this (or any more specific) group
+            // within the current bracket: the bracket established by the
+            // less specific groups already pins down a valid position, so
+            // stop narrowing here.
+            break;
+        };
+        let last = within.iter().rposition(|set| group.matches(set)).unwrap_or(first);
+        bracket = (bracket.start + first)..(bracket.start + last + 1);
+    }
+
+    Some(bracket.end)
+}
+
+impl SubStorages {
+    /// Declares that archetypes containing all of `components` should be
+    /// kept adjacent within `sub`'s `archetypes` list.
+    ///
+    /// Groups only affect where *future* archetypes are inserted; they are
+    /// not retroactively applied to archetypes already registered in `sub`.
+    ///
+    /// # Panics
+    /// Panics if `components` partially overlaps an existing group on `sub`
+    /// (shares some, but not all, components, in neither direction) — see
+    /// [`Group`]'s docs for why nesting is required.
+    pub fn define_group(&mut self, sub: SubStorageId, components: &[ComponentId]) {
+        let group = Group {
+            components: components.to_vec(),
+        };
+        assert!(
+            self[sub].groups.iter().all(|existing| existing.nests_with(&group)),
+            "groups on the same sub-storage must nest (be disjoint or one a subset of the other)",
+        );
+        self[sub].groups.push(group);
+    }
+
+    /// Inserts `archetype` into `sub`'s `archetypes` list.
+    ///
+    /// If `archetype`'s components satisfy one or more of `sub`'s declared
+    /// [`Group`]s, its insertion point is found via
+    /// [`grouped_insertion_point`]. Archetype and component iteration always
+    /// walks `archetypes` in order, so this is the only place grouping needs
+    /// to be enforced.
+    pub(crate) fn insert_archetype(&mut self, sub: SubStorageId, archetype: ArchetypeId) {
+        let data = &self[sub];
+        let components = data.storages.tables.archetype_components(archetype).to_vec();
+        let component_sets: Vec<Vec<ComponentId>> = data
+            .archetypes
+            .iter()
+            .map(|&existing| data.storages.tables.archetype_components(existing).to_vec())
+            .collect();
+
+        let insert_at =
+            grouped_insertion_point(&data.groups, &component_sets, &components).unwrap_or(data.archetypes.len());
+        self[sub].archetypes.insert(insert_at, archetype);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group(components: &[usize]) -> Group {
+        Group {
+            components: components.iter().copied().map(ComponentId::new).collect(),
+        }
+    }
+
+    fn components(ids: &[usize]) -> Vec<ComponentId> {
+        ids.iter().copied().map(ComponentId::new).collect()
+    }
+
+    #[test]
+    fn ungrouped_archetype_has_no_insertion_point() {
+        let groups = [group(&[1])];
+        let existing = [components(&[1])];
+        assert_eq!(grouped_insertion_point(&groups, &existing, &components(&[2])), None);
+    }
+
+    #[test]
+    fn narrows_from_least_to_most_specific_without_splitting_the_broader_group() {
+        // Groups: (A) and (A, B), which nest (the first is a subset of the
+        // second). Existing archetypes, in order: (A), (A, B), (A, C). An
+        // incoming (A, B) archetype must land inside the (A) run *and*
+        // immediately after the existing (A, B) archetype, not just
+        // wherever the (A, B) group alone would put it.
+        let groups = [group(&[1]), group(&[1, 2])];
+        let existing = [components(&[1]), components(&[1, 2]), components(&[1, 3])];
+
+        let insert_at = grouped_insertion_point(&groups, &existing, &components(&[1, 2])).unwrap();
+
+        assert_eq!(insert_at, 2, "must land between the (A,B) and (A,C) archetypes, inside the (A) run");
+    }
+
+    #[test]
+    fn falls_back_to_the_broader_bracket_when_the_narrower_group_is_unrepresented() {
+        // Groups: (A) and (A, B). Existing archetypes: (A), (A, C) — no
+        // archetype yet satisfies (A, B), so narrowing stops at the (A)
+        // group's bracket instead of failing to place the archetype at all.
+        let groups = [group(&[1]), group(&[1, 2])];
+        let existing = [components(&[1]), components(&[1, 3])];
+
+        let insert_at = grouped_insertion_point(&groups, &existing, &components(&[1, 2])).unwrap();
+
+        assert_eq!(insert_at, 2, "stays inside the (A) run even with no existing (A,B) member to narrow against");
+    }
+}
@@ -0,0 +1,394 @@
+use bevy_platform_support::collections::HashMap;
+use std::vec::Vec;
+
+use crate::{
+    archetype::ArchetypeId,
+    component::ComponentId,
+    entity::{Entities, Entity},
+    storage::{EntityLocation, Storages, SubStorageId, SubStorages},
+};
+
+/// The result of merging one source row into a destination archetype.
+pub struct MergedRow {
+    /// The row the entity's components now occupy in the destination
+    /// archetype's table.
+    pub dst_row: u32,
+}
+
+/// A strategy for transferring entities between [`SubStorageId`]s via
+/// [`SubStorages::merge`].
+///
+/// A `Merger` decides *where* an entity's components land in the destination
+/// sub-storage (`assign_archetype`), then *how* they get there
+/// (`merge_archetype`, driven per-component by `merge_components`). [`Move`]
+/// and [`Duplicate`] are the built-in strategies; implement this trait
+/// directly only if neither fits.
+pub trait Merger {
+    /// Returns the archetype in `dst` that entities currently in
+    /// `src_archetype` (a member of `src`) should be placed into, creating
+    /// it (and registering it in `dst`'s `archetypes` list) if `dst` has no
+    /// equivalent archetype yet.
+    fn assign_archetype(
+        &mut self,
+        storages: &mut SubStorages,
+        src: SubStorageId,
+        dst: SubStorageId,
+        src_archetype: ArchetypeId,
+    ) -> ArchetypeId;
+
+    /// Transfers the component data for `rows` (rows in `src_archetype`'s
+    /// table, within `src`) into `dst_archetype`'s table (within `dst`),
+    /// calling [`Merger::merge_components`] once per shared component for
+    /// each row. Returns one [`MergedRow`] per entry of `rows`, in the same
+    /// order as `rows`.
+    ///
+    /// If removing a source row displaces another entity into the vacated
+    /// slot (as a swap-remove does), this method must record that entity's
+    /// new location itself, via [`SubStorages::set_location`] on `storages`,
+    /// as part of processing that row — not by reporting it for the caller
+    /// to apply afterward. [`merge_rows`] (used by both [`Move`] and
+    /// [`Duplicate`]) already does this; a hand-written `Merger` that
+    /// swap-removes source rows itself must do the same.
+    fn merge_archetype(
+        &mut self,
+        storages: &mut SubStorages,
+        src: SubStorageId,
+        dst: SubStorageId,
+        src_archetype: ArchetypeId,
+        dst_archetype: ArchetypeId,
+        rows: &[u32],
+    ) -> Vec<MergedRow>;
+
+    /// Transfers a single component column's data for one row from the
+    /// source storages into the destination storages.
+    ///
+    /// # Safety
+    /// `component` must be present in both `src` and `dst`, and `src_row`/
+    /// `dst_row` must be valid rows for that component's backing storage
+    /// (table column or sparse set) in their respective [`Storages`].
+    unsafe fn merge_components(
+        &mut self,
+        component: ComponentId,
+        src: &mut Storages,
+        dst: &mut Storages,
+        src_row: u32,
+        dst_row: u32,
+    );
+
+    /// Whether this merger requires every entity to be allocated a fresh
+    /// destination id, even when `dst` has no conflicting entity under the
+    /// source id.
+    ///
+    /// [`Move`] returns `false` (the id is preserved); [`Duplicate`] returns
+    /// `true`, since the source copy keeps the original id and a duplicate
+    /// cannot also be recorded in the location index under that same id.
+    fn always_remaps(&self) -> bool {
+        false
+    }
+}
+
+/// Runs `rows` from the highest value down to the lowest, invoking
+/// `process` for each in turn and feeding any swap-remove displacement it
+/// reports straight into `on_displaced` before moving on to the next (lower)
+/// row. Returns each call's non-displacement result, in the same order as
+/// `rows` (*not* processing order).
+///
+/// Processing in descending order guarantees every row this call still has
+/// left to visit is strictly below the row just handled, so it can never be
+/// the row a swap-remove just shuffled something into (if it were one of
+/// `rows`' own members, it would already have been visited and removed
+/// earlier in the same pass). A bystander can still be displaced more than
+/// once within a single pass — each removal shrinks the table and may slide
+/// the *same* entity into a *new* slot — so `on_displaced` is called
+/// immediately after each row instead of collecting every displacement to
+/// replay later: replaying them out of order (e.g. in the original,
+/// non-descending order of `rows`) can leave a repeatedly-displaced entity
+/// recorded at a stale intermediate row instead of the one it actually ends
+/// up in.
+fn process_rows_descending<T>(
+    rows: &[u32],
+    mut process: impl FnMut(u32) -> (T, Option<(Entity, u32)>),
+    mut on_displaced: impl FnMut(Entity, u32),
+) -> Vec<T> {
+    let mut order: Vec<usize> = (0..rows.len()).collect();
+    order.sort_unstable_by_key(|&index| core::cmp::Reverse(rows[index]));
+
+    let mut results: Vec<Option<T>> = (0..rows.len()).map(|_| None).collect();
+    for index in order {
+        let (value, displaced) = process(rows[index]);
+        if let Some((entity, row)) = displaced {
+            on_displaced(entity, row);
+        }
+        results[index] = Some(value);
+    }
+
+    results
+        .into_iter()
+        .map(|value| value.expect("every row index is visited exactly once"))
+        .collect()
+}
+
+/// Merges `rows` (rows of `src_archetype`, within `src`) into
+/// `dst_archetype` (within `dst`), calling `merger.merge_components` for
+/// every component shared by both archetypes.
+///
+/// If `remove_source` is set, each source row is swap-removed immediately
+/// after its data is copied out, and any entity the swap-remove displaces
+/// has its location updated right away (see [`process_rows_descending`]).
+fn merge_rows(
+    merger: &mut impl Merger,
+    storages: &mut SubStorages,
+    src: SubStorageId,
+    dst: SubStorageId,
+    src_archetype: ArchetypeId,
+    dst_archetype: ArchetypeId,
+    rows: &[u32],
+    remove_source: bool,
+) -> Vec<MergedRow> {
+    let components = storages.shared_components(src, dst, src_archetype, dst_archetype);
+
+    process_rows_descending(
+        rows,
+        |src_row| {
+            let dst_row = storages.allocate_row(dst, dst_archetype);
+            let (src_storages, dst_storages) = storages.pair_mut(src, dst);
+            for &component in &components {
+                // Safety: `component` is shared by both archetypes, and
+                // `dst_row` was just allocated for this transfer.
+                unsafe {
+                    merger.merge_components(component, src_storages, dst_storages, src_row, dst_row);
+                }
+            }
+
+            let displaced = remove_source
+                .then(|| storages.swap_remove_row(src, src_archetype, src_row))
+                .flatten();
+            (MergedRow { dst_row }, displaced)
+        },
+        |moved_entity, moved_row| {
+            storages.set_location(
+                moved_entity,
+                EntityLocation {
+                    sub_storage: src,
+                    archetype: src_archetype,
+                    table_row: moved_row,
+                },
+            );
+        },
+    )
+}
+
+/// A [`Merger`] that relocates entities: component data is physically moved
+/// out of the source storages (table columns are `memcpy`'d cell-by-cell,
+/// sparse set entries are transferred), and the vacated source row is
+/// swap-removed, so after the merge the entities exist only in `dst`.
+#[derive(Default)]
+pub struct Move;
+
+impl Merger for Move {
+    fn assign_archetype(
+        &mut self,
+        storages: &mut SubStorages,
+        src: SubStorageId,
+        dst: SubStorageId,
+        src_archetype: ArchetypeId,
+    ) -> ArchetypeId {
+        storages.find_or_clone_archetype(src, dst, src_archetype)
+    }
+
+    fn merge_archetype(
+        &mut self,
+        storages: &mut SubStorages,
+        src: SubStorageId,
+        dst: SubStorageId,
+        src_archetype: ArchetypeId,
+        dst_archetype: ArchetypeId,
+        rows: &[u32],
+    ) -> Vec<MergedRow> {
+        merge_rows(self, storages, src, dst, src_archetype, dst_archetype, rows, true)
+    }
+
+    unsafe fn merge_components(
+        &mut self,
+        component: ComponentId,
+        src: &mut Storages,
+        dst: &mut Storages,
+        src_row: u32,
+        dst_row: u32,
+    ) {
+        // Safety: forwarded from this method's own safety contract. The
+        // component is removed from `src` as part of the move.
+        unsafe { Storages::move_component(component, src, dst, src_row, dst_row) };
+    }
+}
+
+/// A [`Merger`] that copies entities: component data is duplicated into
+/// `dst` via each component's registered clone function, and the source
+/// storages are left untouched, so the source entity keeps its components
+/// and the copy in `dst` is a separate entity under a freshly allocated id
+/// (see [`Merger::always_remaps`]).
+#[derive(Default)]
+pub struct Duplicate;
+
+impl Merger for Duplicate {
+    fn assign_archetype(
+        &mut self,
+        storages: &mut SubStorages,
+        src: SubStorageId,
+        dst: SubStorageId,
+        src_archetype: ArchetypeId,
+    ) -> ArchetypeId {
+        storages.find_or_clone_archetype(src, dst, src_archetype)
+    }
+
+    fn merge_archetype(
+        &mut self,
+        storages: &mut SubStorages,
+        src: SubStorageId,
+        dst: SubStorageId,
+        src_archetype: ArchetypeId,
+        dst_archetype: ArchetypeId,
+        rows: &[u32],
+    ) -> Vec<MergedRow> {
+        merge_rows(self, storages, src, dst, src_archetype, dst_archetype, rows, false)
+    }
+
+    unsafe fn merge_components(
+        &mut self,
+        component: ComponentId,
+        src: &mut Storages,
+        dst: &mut Storages,
+        src_row: u32,
+        dst_row: u32,
+    ) {
+        // Safety: forwarded from this method's own safety contract. `src` is
+        // only read from; the component's registered clone fn is used
+        // in place of a raw `memcpy` so non-`Copy` components are handled
+        // correctly.
+        unsafe { Storages::clone_component(component, src, dst, src_row, dst_row) };
+    }
+
+    fn always_remaps(&self) -> bool {
+        // The source copy keeps its original id, so the destination copy
+        // can never reuse it: the location index is single-valued per
+        // entity id, and the source's entry must keep pointing at `src`.
+        true
+    }
+}
+
+impl SubStorages {
+    /// Moves (or, with [`Duplicate`], copies) `entities` from `src` into
+    /// `dst`, using `merger` to decide the destination archetype and to
+    /// transfer each component. `entity_allocator` is used to mint a fresh
+    /// id for every entity `merger` says must be remapped
+    /// ([`Merger::always_remaps`]).
+    ///
+    /// A [`Move`] always keeps the entity's original id: ids are minted from
+    /// one shared `entity_allocator` across every sub-storage, so the id a
+    /// `Move`d entity already has can never also be recorded under `dst`,
+    /// and there is nothing for it to be remapped away from. A [`Duplicate`]
+    /// always allocates a fresh id for the `dst` copy instead, since the
+    /// source entity keeps its original id and location. Either way, the
+    /// returned map records every remapping that occurred (entities that
+    /// kept their id are not included).
+    ///
+    /// Entities are handled one source archetype at a time. `dst`'s
+    /// `archetypes` list is updated by [`Merger::assign_archetype`] before
+    /// any of that archetype's rows are transferred, and the location index
+    /// entries for those entities are written only after
+    /// [`Merger::merge_archetype`] has returned for the whole archetype. If
+    /// `merge_archetype` panics partway through, none of that archetype's
+    /// entities get a `dst` location, including those whose rows were
+    /// already transferred.
+    pub fn merge(
+        &mut self,
+        src: SubStorageId,
+        dst: SubStorageId,
+        entities: impl IntoIterator<Item = Entity>,
+        entity_allocator: &mut Entities,
+        merger: &mut impl Merger,
+    ) -> HashMap<Entity, Entity> {
+        assert_ne!(src, dst, "cannot merge a sub-storage into itself");
+
+        let mut by_archetype: HashMap<ArchetypeId, Vec<(Entity, u32)>> = HashMap::default();
+        for entity in entities {
+            let Some(location) = self.location(entity) else {
+                continue;
+            };
+            debug_assert_eq!(location.sub_storage, src, "entity is not in `src`");
+            by_archetype
+                .entry(location.archetype)
+                .or_default()
+                .push((entity, location.table_row));
+        }
+
+        let mut remapped = HashMap::default();
+        for (src_archetype, members) in by_archetype {
+            let dst_archetype = merger.assign_archetype(self, src, dst, src_archetype);
+            let rows: Vec<u32> = members.iter().map(|&(_, row)| row).collect();
+            let merged = merger.merge_archetype(self, src, dst, src_archetype, dst_archetype, &rows);
+
+            for ((entity, _), MergedRow { dst_row }) in members.into_iter().zip(merged) {
+                let dst_entity = if merger.always_remaps() {
+                    let fresh = entity_allocator.alloc();
+                    remapped.insert(entity, fresh);
+                    // `entity` (the source copy) keeps its existing `src`
+                    // location; only the fresh id gets the new `dst` one.
+                    fresh
+                } else {
+                    entity
+                };
+
+                self.set_location(
+                    dst_entity,
+                    EntityLocation {
+                        sub_storage: dst,
+                        archetype: dst_archetype,
+                        table_row: dst_row,
+                    },
+                );
+            }
+        }
+
+        remapped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_displacement_within_a_pass_ends_at_its_final_row() {
+        // Mirrors a 3-row archetype table [A@0, C@1, D@2] where merging
+        // {A, C} out removes two of its three rows: D (a bystander) is
+        // displaced once when C's row is removed, then again when A's row
+        // is removed. Only applying each displacement's location update
+        // immediately (rather than replaying them later in some other
+        // order) leaves D recorded at its true final row.
+        let a = Entity::from_raw(0);
+        let c = Entity::from_raw(1);
+        let d = Entity::from_raw(2);
+
+        let mut table = vec![a, c, d];
+        let mut swap_remove = |table: &mut Vec<Entity>, row: u32| -> Option<(Entity, u32)> {
+            let row = row as usize;
+            let was_last = row == table.len() - 1;
+            table.swap_remove(row);
+            (!was_last).then(|| (table[row], row as u32))
+        };
+
+        let mut final_row = HashMap::default();
+        let rows = [0, 1]; // A's row, C's row, in member (non-descending) order
+        process_rows_descending(
+            &rows,
+            |row| (row, swap_remove(&mut table, row)),
+            |entity, row| {
+                final_row.insert(entity, row);
+            },
+        );
+
+        assert_eq!(final_row.get(&d), Some(&0));
+        assert_eq!(table, vec![d]);
+    }
+}
@@ -22,6 +22,8 @@
 
 mod blob_array;
 mod blob_vec;
+mod group;
+mod merge;
 mod resource;
 mod sparse_set;
 mod sub_storage;
@@ -29,6 +31,8 @@ mod table;
 mod thin_array_ptr;
 
 use bevy_platform_support::collections::HashSet;
+pub use group::*;
+pub use merge::*;
 pub use resource::*;
 pub use sparse_set::*;
 pub use sub_storage::*;
@@ -1,14 +1,20 @@
 use core::ops::{Index, IndexMut};
-use std::vec::Vec;
+use std::{any::TypeId, vec::Vec};
 
 use bevy_utils::TypeIdMap;
 
-use crate::{archetype::ArchetypeId, storage::Storages};
+use crate::{
+    archetype::ArchetypeId,
+    component::ComponentId,
+    entity::{Entities, Entity},
+    storage::{Group, Storages},
+};
 
 #[derive(Default)]
 pub struct SubStorages {
     pub sub_storages: Vec<SubStorageData>,
     pub indices: TypeIdMap<SubStorageId>,
+    locations: SubStorageLocations,
 }
 
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
@@ -18,6 +24,9 @@ pub struct SubStorageData {
     pub id: SubStorageId,
     pub archetypes: Vec<ArchetypeId>,
     pub storages: Storages,
+    /// Component groups declared via [`SubStorages::define_group`], used to
+    /// keep related archetypes contiguous within `archetypes`.
+    pub groups: Vec<Group>,
 }
 
 pub trait SubStorage: Send + Sync + 'static {}
@@ -26,20 +35,247 @@ pub struct MainStorage;
 
 impl SubStorage for MainStorage {}
 
+/// The location of an [`Entity`] within the partitioned [`SubStorages`].
+///
+/// This pinpoints the exact sub-storage, archetype, and table row an entity's
+/// components currently live in, mirroring [`crate::entity::EntityLocation`]
+/// but scoped to a single [`SubStorageId`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EntityLocation {
+    /// The sub-storage the entity's components are stored in.
+    pub sub_storage: SubStorageId,
+    /// The archetype the entity belongs to, within `sub_storage`.
+    pub archetype: ArchetypeId,
+    /// The row of the entity's components within the archetype's table.
+    pub table_row: u32,
+}
+
+/// A dense, generation-checked `Entity -> EntityLocation` index.
+///
+/// This is kept separate from [`crate::entity::Entities`] because it tracks a
+/// different axis of truth (which sub-storage an entity's data lives in,
+/// rather than whether the entity id itself is alive), but it follows the
+/// same shape: a `Vec` indexed by entity index, with a generation stored
+/// alongside each slot so that a lookup for a stale, reused entity id cannot
+/// return a different entity's location.
+#[derive(Default)]
+struct SubStorageLocations {
+    slots: Vec<LocationSlot>,
+}
+
+#[derive(Clone, Copy, Default)]
+struct LocationSlot {
+    generation: u32,
+    location: Option<EntityLocation>,
+}
+
+impl SubStorageLocations {
+    fn get(&self, entity: Entity) -> Option<EntityLocation> {
+        let slot = self.slots.get(entity.index() as usize)?;
+        if slot.generation != entity.generation() {
+            return None;
+        }
+        slot.location
+    }
+
+    fn set(&mut self, entity: Entity, location: EntityLocation) {
+        let index = entity.index() as usize;
+        if index >= self.slots.len() {
+            self.slots.resize(index + 1, LocationSlot::default());
+        }
+        let slot = &mut self.slots[index];
+        slot.generation = entity.generation();
+        slot.location = Some(location);
+    }
+
+    fn clear(&mut self, entity: Entity) {
+        if let Some(slot) = self.slots.get_mut(entity.index() as usize) {
+            if slot.generation == entity.generation() {
+                slot.location = None;
+            }
+        }
+    }
+}
+
 impl SubStorages {
     pub const MAIN_STORAGE: SubStorageId = SubStorageId(0);
 
     pub fn new() -> Self {
         Self {
-            sub_storages: vec![SubStorageInfo {
-                id: SubWorldId(0),
+            sub_storages: vec![SubStorageData {
+                id: Self::MAIN_STORAGE,
                 archetypes: Vec::new(),
                 storages: Storages::default(),
+                groups: Vec::new(),
             }],
-            indices: vec![(TypeId::of::<MainStorage>(), SubWorldId(0))]
+            indices: vec![(TypeId::of::<MainStorage>(), Self::MAIN_STORAGE)]
                 .into_iter()
                 .collect(),
+            locations: SubStorageLocations::default(),
+        }
+    }
+
+    /// Returns the [`SubStorageId`] registered for marker type `S`, or
+    /// `None` if [`SubStorages::register::<S>`](SubStorages::register) has
+    /// never been called.
+    pub fn id_of<S: SubStorage>(&self) -> Option<SubStorageId> {
+        self.indices.get(&TypeId::of::<S>()).copied()
+    }
+
+    /// Registers a new sub-storage addressable by the zero-sized marker type
+    /// `S`, allocating it a fresh [`SubStorageId`] and an empty `Storages`
+    /// of its own.
+    ///
+    /// Calling this more than once for the same `S` returns the id from the
+    /// first registration rather than allocating a second sub-storage.
+    pub fn register<S: SubStorage>(&mut self) -> SubStorageId {
+        if let Some(id) = self.id_of::<S>() {
+            return id;
         }
+
+        let id = SubStorageId(self.sub_storages.len() as u32);
+        self.sub_storages.push(SubStorageData {
+            id,
+            archetypes: Vec::new(),
+            storages: Storages::default(),
+            groups: Vec::new(),
+        });
+        self.indices.insert(TypeId::of::<S>(), id);
+        id
+    }
+
+    /// Returns the [`EntityLocation`] of `entity`, or `None` if it has no
+    /// recorded location (it has never been spawned, or it has since been
+    /// despawned).
+    #[inline]
+    pub fn location(&self, entity: Entity) -> Option<EntityLocation> {
+        self.locations.get(entity)
+    }
+
+    /// Records `entity`'s current location.
+    ///
+    /// Must be called whenever `entity` is spawned, has components
+    /// inserted/removed in a way that changes its archetype, or is moved
+    /// between sub-storages, so that [`SubStorages::location`] stays
+    /// accurate.
+    #[inline]
+    pub(crate) fn set_location(&mut self, entity: Entity, location: EntityLocation) {
+        self.locations.set(entity, location);
+    }
+
+    /// Clears `entity`'s recorded location.
+    ///
+    /// Must be called when `entity` is despawned.
+    #[inline]
+    pub(crate) fn clear_location(&mut self, entity: Entity) {
+        self.locations.clear(entity);
+    }
+
+    /// Returns the [`ArchetypeId`] in `dst` whose component composition
+    /// matches `src_archetype` (a member of `src`), cloning the archetype's
+    /// shape into `dst` if it has no equivalent yet.
+    pub(crate) fn find_or_clone_archetype(
+        &mut self,
+        src: SubStorageId,
+        dst: SubStorageId,
+        src_archetype: ArchetypeId,
+    ) -> ArchetypeId {
+        let components = self[src].storages.tables.archetype_components(src_archetype);
+        if let Some(&existing) = self[dst].archetypes.iter().find(|&&archetype| {
+            self[dst].storages.tables.archetype_components(archetype) == components
+        }) {
+            return existing;
+        }
+
+        let (src_storages, dst_storages) = self.pair_mut(src, dst);
+        let archetype = dst_storages.tables.clone_archetype_shape(&src_storages.tables, src_archetype);
+        self.insert_archetype(dst, archetype);
+        archetype
+    }
+
+    /// Returns the [`ComponentId`]s present in both archetypes, which is
+    /// exactly the set of components a merge between them needs to
+    /// transfer.
+    pub(crate) fn shared_components(
+        &self,
+        src: SubStorageId,
+        dst: SubStorageId,
+        src_archetype: ArchetypeId,
+        dst_archetype: ArchetypeId,
+    ) -> Vec<ComponentId> {
+        let dst_components = self[dst].storages.tables.archetype_components(dst_archetype);
+        self[src]
+            .storages
+            .tables
+            .archetype_components(src_archetype)
+            .iter()
+            .copied()
+            .filter(|component| dst_components.contains(component))
+            .collect()
+    }
+
+    /// Allocates a new, uninitialized row in `dst_archetype`'s table within
+    /// `dst`, to be filled in by a [`Merger`](crate::storage::Merger).
+    pub(crate) fn allocate_row(&mut self, dst: SubStorageId, dst_archetype: ArchetypeId) -> u32 {
+        self[dst].storages.tables.allocate_row(dst_archetype)
+    }
+
+    /// Splits `self` into a disjoint pair of mutable [`Storages`]
+    /// references, one for `src` and one for `dst`.
+    pub(crate) fn pair_mut(&mut self, src: SubStorageId, dst: SubStorageId) -> (&mut Storages, &mut Storages) {
+        assert_ne!(src, dst, "cannot borrow the same sub-storage twice");
+        let (lo, hi) = (src.as_usize().min(dst.as_usize()), src.as_usize().max(dst.as_usize()));
+        let (left, right) = self.sub_storages.split_at_mut(hi);
+        let (a, b) = (&mut left[lo].storages, &mut right[0].storages);
+        if src.as_usize() < dst.as_usize() {
+            (a, b)
+        } else {
+            (b, a)
+        }
+    }
+
+    /// Spawns a brand-new entity directly into the sub-storage registered
+    /// for marker type `S`, allocating it a row in `archetype`.
+    ///
+    /// `entity_allocator` must be the same [`Entities`](crate::entity::Entities)
+    /// the rest of the [`World`](crate::world::World) allocates ids from, so
+    /// the returned id can never collide with one handed out elsewhere.
+    ///
+    /// This is the routing primitive behind `Commands::spawn_in::<S>`: the
+    /// system-parameter layer resolves the archetype for the bundle being
+    /// spawned and is responsible for writing the bundle's component values
+    /// into the row of the returned [`EntityLocation`].
+    pub fn spawn_in<S: SubStorage>(
+        &mut self,
+        entity_allocator: &mut Entities,
+        archetype: ArchetypeId,
+    ) -> (Entity, EntityLocation) {
+        let sub_storage = self.register::<S>();
+        if !self[sub_storage].archetypes.contains(&archetype) {
+            self.insert_archetype(sub_storage, archetype);
+        }
+
+        let table_row = self.allocate_row(sub_storage, archetype);
+        let entity = entity_allocator.alloc();
+        let location = EntityLocation {
+            sub_storage,
+            archetype,
+            table_row,
+        };
+        self.set_location(entity, location);
+        (entity, location)
+    }
+
+    /// Swap-removes `row` out of `archetype`'s table within `sub_storage`,
+    /// returning the entity (and its new row) that was moved into the
+    /// vacated slot, if any.
+    pub(crate) fn swap_remove_row(
+        &mut self,
+        sub_storage: SubStorageId,
+        archetype: ArchetypeId,
+        row: u32,
+    ) -> Option<(Entity, u32)> {
+        self[sub_storage].storages.tables.swap_remove_row(archetype, row)
     }
 }
 
@@ -66,3 +302,78 @@ impl SubStorageId {
         self.0 as usize
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn location_rejects_a_stale_reused_index() {
+        let mut entities = Entities::default();
+        let mut locations = SubStorageLocations::default();
+
+        let first = entities.alloc();
+        locations.set(
+            first,
+            EntityLocation {
+                sub_storage: SubStorageId(0),
+                archetype: ArchetypeId::EMPTY,
+                table_row: 0,
+            },
+        );
+        assert!(locations.get(first).is_some());
+
+        // Freeing `first` and allocating again reuses its index but bumps
+        // its generation; `first`'s id is now stale.
+        entities.free(first);
+        let second = entities.alloc();
+        assert_eq!(first.index(), second.index());
+        assert_ne!(first.generation(), second.generation());
+
+        assert!(
+            locations.get(first).is_none(),
+            "a stale id must not resolve to the location its reused index now holds"
+        );
+
+        locations.set(
+            second,
+            EntityLocation {
+                sub_storage: SubStorageId(0),
+                archetype: ArchetypeId::EMPTY,
+                table_row: 1,
+            },
+        );
+        assert_eq!(locations.get(second).unwrap().table_row, 1);
+    }
+
+    #[test]
+    fn clear_is_a_no_op_for_a_stale_generation() {
+        let mut entities = Entities::default();
+        let mut locations = SubStorageLocations::default();
+
+        let first = entities.alloc();
+        locations.set(
+            first,
+            EntityLocation {
+                sub_storage: SubStorageId(0),
+                archetype: ArchetypeId::EMPTY,
+                table_row: 0,
+            },
+        );
+        entities.free(first);
+        let second = entities.alloc();
+        locations.set(
+            second,
+            EntityLocation {
+                sub_storage: SubStorageId(0),
+                archetype: ArchetypeId::EMPTY,
+                table_row: 2,
+            },
+        );
+
+        // Clearing under the stale id must not touch the slot `second` now
+        // occupies.
+        locations.clear(first);
+        assert_eq!(locations.get(second).unwrap().table_row, 2);
+    }
+}